@@ -5,11 +5,16 @@ use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 use std::collections::HashMap;
 use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "lowercase")]
 enum TxAction {
     DEPOSIT,
@@ -17,12 +22,76 @@ enum TxAction {
     DISPUTE,
     RESOLVE,
     CHARGEBACK,
+    // named reserve identified by this record's `tx` id: moves `amount` out
+    // of `available` into a tracked reserve pool. UNRESERVE references that
+    // same id via its own `tx` field to return the funds, the same way
+    // RESOLVE/CHARGEBACK reference the disputed deposit's `tx` id
+    RESERVE,
+    UNRESERVE,
+    // overlay lock identified by this record's `tx` id: caps how much of
+    // `available` may be withdrawn until UNLOCK references that id
+    LOCK,
+    UNLOCK,
 }
 
-type TxAmount = Option<Decimal>;
+// lifecycle of a disputable transaction, tracked independently of the
+// `Transaction` record itself so invalid transitions can be rejected; a tx is
+// disputable only once, while it is still `Processed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, PartialEq)]
+enum LedgerError {
+    NotEnoughFunds(u16, Decimal),
+    UnknownTx(u16, u32),
+    AlreadyDisputed(u16, u32),
+    NotDisputed(u16, u32),
+    FrozenAccount(u16),
+    AlreadyReserved(u16, u32),
+    AlreadyLocked(u16, u32),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::NotEnoughFunds(client, amount) => write!(
+                f,
+                "client {client} has insufficient available funds to withdraw {amount}"
+            ),
+            LedgerError::UnknownTx(client, tx) => {
+                write!(f, "client {client} referenced unknown or non-disputable tx {tx}")
+            }
+            LedgerError::AlreadyDisputed(client, tx) => {
+                write!(f, "client {client} tx {tx} is already under dispute")
+            }
+            LedgerError::NotDisputed(client, tx) => {
+                write!(f, "client {client} tx {tx} is not currently disputed")
+            }
+            LedgerError::FrozenAccount(client) => write!(f, "client {client} account is locked"),
+            LedgerError::AlreadyReserved(client, id) => {
+                write!(f, "client {client} reserve id {id} is already active")
+            }
+            LedgerError::AlreadyLocked(client, id) => {
+                write!(f, "client {client} lock id {id} is already active")
+            }
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Tx {
+impl Error for LedgerError {}
+
+// wire format shared by the batch CSV and the line protocol: every row has
+// the same four columns, but whether `amount` is required depends on
+// `action`. `Transaction::try_from` below validates that before the record
+// ever reaches `process_tx`, instead of leaving it as a `None` to unwrap deep
+// inside `deposit`/`withdraw`/`hold`/`release`
+#[derive(Deserialize, Debug, Clone)]
+struct TransactionRecord {
     #[serde(rename = "type")]
     action: TxAction,
 
@@ -30,16 +99,170 @@ struct Tx {
     tx: u32,
 
     #[serde(default)]
-    amount: TxAmount,
+    amount: Option<Decimal>,
+}
 
-    #[serde(default)]
-    is_disputed: bool,
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    MissingAmount(TxAction),
+    UnexpectedAmount(TxAction),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingAmount(action) => {
+                write!(f, "{action:?} record is missing a required amount")
+            }
+            ParseError::UnexpectedAmount(action) => {
+                write!(f, "{action:?} record must not specify an amount")
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+fn reject_amount(action: TxAction, amount: Option<Decimal>) -> Result<(), ParseError> {
+    match amount {
+        Some(_) => Err(ParseError::UnexpectedAmount(action)),
+        None => Ok(()),
+    }
+}
+
+// replaces the old single `Tx` struct (whose `amount` was `Option<Decimal>`
+// for every action) with one variant per action, each carrying exactly the
+// fields that action needs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "TransactionRecord")]
+enum Transaction {
+    Deposit { client: u16, tx: u32, amount: Decimal },
+    Withdrawal { client: u16, tx: u32, amount: Decimal },
+    Dispute { client: u16, tx: u32 },
+    Resolve { client: u16, tx: u32 },
+    Chargeback { client: u16, tx: u32 },
+    Reserve { client: u16, tx: u32, amount: Decimal },
+    Unreserve { client: u16, tx: u32 },
+    Lock { client: u16, tx: u32, amount: Decimal },
+    Unlock { client: u16, tx: u32 },
 }
 
-impl Tx {
+impl TryFrom<TransactionRecord> for Transaction {
+    type Error = ParseError;
+
+    fn try_from(record: TransactionRecord) -> Result<Self, Self::Error> {
+        let TransactionRecord {
+            action,
+            client,
+            tx,
+            amount,
+        } = record;
+
+        match action {
+            TxAction::DEPOSIT => Ok(Transaction::Deposit {
+                client,
+                tx,
+                amount: amount.ok_or(ParseError::MissingAmount(action))?,
+            }),
+            TxAction::WITHDRAWAL => Ok(Transaction::Withdrawal {
+                client,
+                tx,
+                amount: amount.ok_or(ParseError::MissingAmount(action))?,
+            }),
+            TxAction::RESERVE => Ok(Transaction::Reserve {
+                client,
+                tx,
+                amount: amount.ok_or(ParseError::MissingAmount(action))?,
+            }),
+            TxAction::LOCK => Ok(Transaction::Lock {
+                client,
+                tx,
+                amount: amount.ok_or(ParseError::MissingAmount(action))?,
+            }),
+            TxAction::DISPUTE => {
+                reject_amount(action, amount)?;
+                Ok(Transaction::Dispute { client, tx })
+            }
+            TxAction::RESOLVE => {
+                reject_amount(action, amount)?;
+                Ok(Transaction::Resolve { client, tx })
+            }
+            TxAction::CHARGEBACK => {
+                reject_amount(action, amount)?;
+                Ok(Transaction::Chargeback { client, tx })
+            }
+            TxAction::UNRESERVE => {
+                reject_amount(action, amount)?;
+                Ok(Transaction::Unreserve { client, tx })
+            }
+            TxAction::UNLOCK => {
+                reject_amount(action, amount)?;
+                Ok(Transaction::Unlock { client, tx })
+            }
+        }
+    }
+}
+
+impl Transaction {
+    fn client(&self) -> u16 {
+        match self {
+            Transaction::Deposit { client, .. }
+            | Transaction::Withdrawal { client, .. }
+            | Transaction::Dispute { client, .. }
+            | Transaction::Resolve { client, .. }
+            | Transaction::Chargeback { client, .. }
+            | Transaction::Reserve { client, .. }
+            | Transaction::Unreserve { client, .. }
+            | Transaction::Lock { client, .. }
+            | Transaction::Unlock { client, .. } => *client,
+        }
+    }
+
+    fn tx(&self) -> u32 {
+        match self {
+            Transaction::Deposit { tx, .. }
+            | Transaction::Withdrawal { tx, .. }
+            | Transaction::Dispute { tx, .. }
+            | Transaction::Resolve { tx, .. }
+            | Transaction::Chargeback { tx, .. }
+            | Transaction::Reserve { tx, .. }
+            | Transaction::Unreserve { tx, .. }
+            | Transaction::Lock { tx, .. }
+            | Transaction::Unlock { tx, .. } => *tx,
+        }
+    }
+
+    fn amount(&self) -> Option<Decimal> {
+        match self {
+            Transaction::Deposit { amount, .. }
+            | Transaction::Withdrawal { amount, .. }
+            | Transaction::Reserve { amount, .. }
+            | Transaction::Lock { amount, .. } => Some(*amount),
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::Chargeback { .. }
+            | Transaction::Unreserve { .. }
+            | Transaction::Unlock { .. } => None,
+        }
+    }
+
+    fn action(&self) -> TxAction {
+        match self {
+            Transaction::Deposit { .. } => TxAction::DEPOSIT,
+            Transaction::Withdrawal { .. } => TxAction::WITHDRAWAL,
+            Transaction::Dispute { .. } => TxAction::DISPUTE,
+            Transaction::Resolve { .. } => TxAction::RESOLVE,
+            Transaction::Chargeback { .. } => TxAction::CHARGEBACK,
+            Transaction::Reserve { .. } => TxAction::RESERVE,
+            Transaction::Unreserve { .. } => TxAction::UNRESERVE,
+            Transaction::Lock { .. } => TxAction::LOCK,
+            Transaction::Unlock { .. } => TxAction::UNLOCK,
+        }
+    }
+
     fn is_disputable(&self) -> bool {
         // assumption: only allow disputes on deposits
-        self.action == TxAction::DEPOSIT
+        matches!(self, Transaction::Deposit { .. })
     }
 }
 
@@ -49,6 +272,11 @@ struct Account {
     available: Decimal,
     held: Decimal,
     is_locked: bool,
+    // named reserves, keyed by the id of the RESERVE tx that created them
+    reserves: HashMap<u32, Decimal>,
+    // overlay withdrawal locks, keyed by the id of the LOCK tx that created
+    // them; overlapping locks take the maximum rather than summing
+    locks: HashMap<u32, Decimal>,
 }
 
 // use custom serialization here to both
@@ -59,63 +287,115 @@ impl Serialize for Account {
     where
         S: Serializer,
     {
-        let mut state = serializer.serialize_struct("Account", 5)?;
+        let mut state = serializer.serialize_struct("Account", 7)?;
         state.serialize_field("client", &self.client)?;
         state.serialize_field("available", &self.available.round_dp(4).normalize())?;
         state.serialize_field("held", &self.held.round_dp(4).normalize())?;
         state.serialize_field("total", &self.total().round_dp(4).normalize())?;
         state.serialize_field("locked", &self.is_locked)?;
+        state.serialize_field("reserved", &self.reserved().round_dp(4).normalize())?;
+        state.serialize_field("frozen", &self.frozen().round_dp(4).normalize())?;
         state.end()
     }
 }
 
 impl Account {
     fn total(&self) -> Decimal {
-        self.available + self.held
+        self.available + self.held + self.reserved()
     }
 
-    fn deposit(&mut self, amount: TxAmount) {
-        if !self.is_locked {
-            if let Some(value) = amount {
-                self.available += value;
-            }
+    fn reserved(&self) -> Decimal {
+        self.reserves.values().sum()
+    }
+
+    // the amount of `available` that no active lock permits withdrawing;
+    // overlapping locks take the maximum, they don't stack
+    fn frozen(&self) -> Decimal {
+        self.locks.values().copied().max().unwrap_or(dec!(0))
+    }
+
+    fn withdrawable(&self) -> Decimal {
+        (self.available - self.frozen()).max(dec!(0))
+    }
+
+    fn deposit(&mut self, amount: Decimal) -> Result<(), LedgerError> {
+        if self.is_locked {
+            return Err(LedgerError::FrozenAccount(self.client));
         }
+        self.available += amount;
+        Ok(())
     }
 
-    fn withdraw(&mut self, amount: TxAmount) {
-        if !self.is_locked {
-            if let Some(value) = amount {
-                if value <= self.available {
-                    self.available -= value;
-                }
-            }
+    fn withdraw(&mut self, amount: Decimal) -> Result<(), LedgerError> {
+        if self.is_locked {
+            return Err(LedgerError::FrozenAccount(self.client));
+        }
+        if amount > self.withdrawable() {
+            return Err(LedgerError::NotEnoughFunds(self.client, amount));
+        }
+        self.available -= amount;
+        Ok(())
+    }
+
+    fn hold(&mut self, amount: Decimal) {
+        self.available -= amount;
+        self.held += amount;
+    }
+
+    fn release(&mut self, amount: Decimal) {
+        self.available += amount;
+        self.held -= amount;
+    }
+
+    fn chargeback(&mut self, amount: Decimal) {
+        self.held -= amount;
+        self.is_locked = true;
+    }
+
+    fn reserve(&mut self, id: u32, amount: Decimal) -> Result<(), LedgerError> {
+        if self.is_locked {
+            return Err(LedgerError::FrozenAccount(self.client));
+        }
+        if self.reserves.contains_key(&id) {
+            return Err(LedgerError::AlreadyReserved(self.client, id));
+        }
+        if amount > self.available {
+            return Err(LedgerError::NotEnoughFunds(self.client, amount));
         }
+        self.available -= amount;
+        self.reserves.insert(id, amount);
+        Ok(())
     }
 
-    fn hold(&mut self, amount: TxAmount) {
-        if let Some(value) = amount {
-            self.available -= value;
-            self.held += value;
+    fn unreserve(&mut self, id: u32) -> Result<(), LedgerError> {
+        match self.reserves.remove(&id) {
+            Some(value) => {
+                self.available += value;
+                Ok(())
+            }
+            None => Err(LedgerError::UnknownTx(self.client, id)),
         }
     }
 
-    fn release(&mut self, amount: TxAmount) {
-        if let Some(value) = amount {
-            self.available += value;
-            self.held -= value;
+    fn lock(&mut self, id: u32, amount: Decimal) -> Result<(), LedgerError> {
+        if self.locks.contains_key(&id) {
+            return Err(LedgerError::AlreadyLocked(self.client, id));
         }
+        self.locks.insert(id, amount);
+        Ok(())
     }
 
-    fn chargeback(&mut self, amount: TxAmount) {
-        if let Some(value) = amount {
-            self.held -= value;
-            self.is_locked = true;
+    fn unlock(&mut self, id: u32) -> Result<(), LedgerError> {
+        match self.locks.remove(&id) {
+            Some(_) => Ok(()),
+            None => Err(LedgerError::UnknownTx(self.client, id)),
         }
     }
 }
 
 type AccountMap = HashMap<u16, Account>;
-type TxMap = HashMap<u32, Tx>;
+type TxMap = HashMap<u32, Transaction>;
+type StateMap = HashMap<(u16, u32), TxState>;
 
 fn ensure_account(client: u16, accounts: &mut AccountMap) -> () {
     if !accounts.contains_key(&client) {
@@ -126,85 +406,277 @@ fn ensure_account(client: u16, accounts: &mut AccountMap) -> () {
                 available: dec!(0),
                 held: dec!(0),
                 is_locked: false,
+                reserves: HashMap::new(),
+                locks: HashMap::new(),
             },
         );
     }
 }
 
-fn handle_dispute_action(account: &mut Account, disputed_tx: &mut Tx, action: &TxAction) {
-    match action {
-        TxAction::DISPUTE => {
-            // assumption: disputes of transactions already under dispute can be ignored
-            if !disputed_tx.is_disputed {
-                disputed_tx.is_disputed = true;
-                account.hold(disputed_tx.amount);
-            }
+fn handle_dispute_action(
+    account: &mut Account,
+    disputed_tx: &Transaction,
+    state: &mut TxState,
+    action: &TxAction,
+) -> Result<(), LedgerError> {
+    let client = disputed_tx.client();
+    let tx = disputed_tx.tx();
+    let amount = disputed_tx
+        .amount()
+        .expect("only deposits are disputable and always carry an amount");
+
+    match (action, *state) {
+        (TxAction::DISPUTE, TxState::Processed) => {
+            *state = TxState::Disputed;
+            account.hold(amount);
+            Ok(())
         }
-        TxAction::RESOLVE => {
-            // assumption: a transaction that isn't under dispute cannot be resolved
-            if disputed_tx.is_disputed {
-                disputed_tx.is_disputed = false;
-                account.release(disputed_tx.amount);
-            }
+        // covers a second dispute as well as disputing a tx that has already
+        // been resolved or charged back: only a freshly processed tx is disputable
+        (TxAction::DISPUTE, _) => Err(LedgerError::AlreadyDisputed(client, tx)),
+        (TxAction::RESOLVE, TxState::Disputed) => {
+            *state = TxState::Resolved;
+            account.release(amount);
+            Ok(())
         }
-        TxAction::CHARGEBACK => {
-            // assumption: a transaction that isn't under dispute cannot be charged back
-            if disputed_tx.is_disputed {
-                disputed_tx.is_disputed = false;
-                account.chargeback(disputed_tx.amount);
-            }
+        (TxAction::RESOLVE, _) => Err(LedgerError::NotDisputed(client, tx)),
+        (TxAction::CHARGEBACK, TxState::Disputed) => {
+            *state = TxState::ChargedBack;
+            account.chargeback(amount);
+            Ok(())
         }
-        _ => (), // neither DEPOSIT nor WITHDRAWAL affect dispute lifecycle
-    };
+        (TxAction::CHARGEBACK, _) => Err(LedgerError::NotDisputed(client, tx)),
+        (TxAction::DEPOSIT, _)
+        | (TxAction::WITHDRAWAL, _)
+        | (TxAction::RESERVE, _)
+        | (TxAction::UNRESERVE, _)
+        | (TxAction::LOCK, _)
+        | (TxAction::UNLOCK, _) => {
+            unreachable!("only called for dispute-lifecycle actions")
+        }
+    }
 }
 
 fn process_tx(
-    tx: &mut Tx,
+    tx: &Transaction,
     disputable_txs: &mut TxMap,
+    states: &mut StateMap,
     accounts: &mut AccountMap,
-) -> Result<(), Box<dyn Error>> {
-    ensure_account(tx.client, accounts);
-
-    if let Some(account) = accounts.get_mut(&tx.client) {
-        match tx.action {
-            TxAction::DEPOSIT => account.deposit(tx.amount),
-            TxAction::WITHDRAWAL => account.withdraw(tx.amount),
-            TxAction::DISPUTE | TxAction::RESOLVE | TxAction::CHARGEBACK => {
-                if let Some(disputed_tx) = disputable_txs.get_mut(&tx.tx) {
-                    // assumption: disallow client x to dispute tx of client y, where x != y
-                    if disputed_tx.is_disputable() && disputed_tx.client == tx.client {
-                        handle_dispute_action(account, disputed_tx, &tx.action);
-                    }
+) -> Result<(), LedgerError> {
+    ensure_account(tx.client(), accounts);
+    let account = accounts
+        .get_mut(&tx.client())
+        .expect("account was just ensured above");
+
+    match tx {
+        Transaction::Deposit { amount, .. } => account.deposit(*amount),
+        Transaction::Withdrawal { amount, .. } => account.withdraw(*amount),
+        Transaction::Dispute { .. } | Transaction::Resolve { .. } | Transaction::Chargeback { .. } => {
+            // assumption: disallow client x to dispute tx of client y, where x != y
+            let disputed_tx = disputable_txs
+                .get(&tx.tx())
+                .filter(|d| d.is_disputable() && d.client() == tx.client())
+                .ok_or(LedgerError::UnknownTx(tx.client(), tx.tx()))?;
+
+            let state = states
+                .entry((tx.client(), tx.tx()))
+                .or_insert(TxState::Processed);
+
+            handle_dispute_action(account, disputed_tx, state, &tx.action())
+        }
+        Transaction::Reserve { amount, .. } => account.reserve(tx.tx(), *amount),
+        Transaction::Unreserve { .. } => account.unreserve(tx.tx()),
+        Transaction::Lock { amount, .. } => account.lock(tx.tx(), *amount),
+        Transaction::Unlock { .. } => account.unlock(tx.tx()),
+    }
+}
+
+// bundles the three maps `process_tx` needs so batch shards and the long-lived
+// server share one notion of "the ledger so far" instead of threading three
+// arguments through every caller
+struct Ledger {
+    accounts: AccountMap,
+    disputable_txs: TxMap,
+    states: StateMap,
+    // minimum total (available + held + reserved) an unlocked account may hold; once any
+    // successful mutation drops it below this, the account is reaped
+    existential_deposit: Option<Decimal>,
+    // net deposits minus withdrawals minus chargebacks across every client,
+    // including clients that have since been reaped as dust
+    total_issuance: Decimal,
+}
+
+impl Ledger {
+    fn new(existential_deposit: Option<Decimal>) -> Self {
+        Ledger {
+            accounts: AccountMap::new(),
+            disputable_txs: TxMap::new(),
+            states: StateMap::new(),
+            existential_deposit,
+            total_issuance: dec!(0),
+        }
+    }
+
+    fn apply(&mut self, tx: Transaction) -> Result<(), LedgerError> {
+        let disputed_amount = self.disputable_txs.get(&tx.tx()).and_then(|d| d.amount());
+
+        let result = process_tx(
+            &tx,
+            &mut self.disputable_txs,
+            &mut self.states,
+            &mut self.accounts,
+        );
+
+        if result.is_ok() {
+            match &tx {
+                Transaction::Deposit { amount, .. } => {
+                    self.total_issuance += *amount;
+                }
+                Transaction::Withdrawal { amount, .. } => {
+                    self.total_issuance -= *amount;
+                }
+                Transaction::Chargeback { .. } => {
+                    self.total_issuance -= disputed_amount.unwrap_or(dec!(0));
                 }
+                Transaction::Dispute { .. }
+                | Transaction::Resolve { .. }
+                | Transaction::Reserve { .. }
+                | Transaction::Unreserve { .. }
+                | Transaction::Lock { .. }
+                | Transaction::Unlock { .. } => (),
             }
+
+            // a deposit can leave `total()` below the threshold too (e.g. a new
+            // account's first deposit), not just a withdrawal, so check the
+            // threshold unconditionally after any successful mutation
+            self.reap_dust(tx.client());
+        }
+
+        if tx.is_disputable() {
+            self.disputable_txs.insert(tx.tx(), tx);
+        }
+
+        result
+    }
+
+    // removes a client's account entirely once its total drops below the
+    // existential deposit, so dust accounts don't accumulate over huge inputs;
+    // a charged-back account stays frozen forever, so it's never reaped even
+    // if dust — removing it would let a later deposit recreate it unlocked
+    // via `ensure_account` and silently erase the fraud lock
+    fn reap_dust(&mut self, client: u16) {
+        let Some(threshold) = self.existential_deposit else {
+            return;
         };
+
+        let is_dust = self
+            .accounts
+            .get(&client)
+            .is_some_and(|account| !account.is_locked && account.total() < threshold);
+
+        if is_dust {
+            self.accounts.remove(&client);
+        }
     }
+}
 
-    Ok(())
+// drains a stream of txs for a single client shard into its own account/tx
+// state, so the same logic can run either inline (single-threaded mode) or
+// inside a worker thread that owns an exclusive slice of clients
+fn process_tx_stream<I: Iterator<Item = Transaction>>(
+    tx_iter: I,
+    existential_deposit: Option<Decimal>,
+) -> Ledger {
+    let mut ledger = Ledger::new(existential_deposit);
+
+    for tx in tx_iter {
+        let client = tx.client();
+        let tx_id = tx.tx();
+
+        if let Err(e) = ledger.apply(tx) {
+            eprintln!("rejected tx {client}/{tx_id}: {e}");
+        }
+    }
+
+    ledger
 }
 
-fn balance_accounts(mut tx_reader: Reader<File>) -> Result<AccountMap, Box<dyn Error>> {
-    let mut accounts: AccountMap = AccountMap::new();
-    let mut disputable_txs = TxMap::new();
+fn balance_accounts(
+    mut tx_reader: Reader<File>,
+    existential_deposit: Option<Decimal>,
+) -> Result<Ledger, Box<dyn Error>> {
+    let txs = tx_reader
+        .deserialize::<Transaction>()
+        .collect::<Result<Vec<Transaction>, _>>()?;
+
+    Ok(process_tx_stream(txs.into_iter(), existential_deposit))
+}
 
-    let tx_iter = tx_reader.deserialize::<Tx>();
+// routes a client to a fixed worker so every tx for that client lands on the
+// same shard and is applied in input order, preserving the invariant the
+// dispute lifecycle depends on (a dispute must see its deposit already applied)
+fn shard_index(client: u16, threads: usize) -> usize {
+    (client as usize) % threads
+}
 
-    for tx_result in tx_iter {
-        let mut tx = tx_result?;
-        process_tx(&mut tx, &mut disputable_txs, &mut accounts)?;
+fn shard_by_client(txs: Vec<Transaction>, threads: usize) -> Vec<Vec<Transaction>> {
+    let mut shards: Vec<Vec<Transaction>> = (0..threads).map(|_| Vec::new()).collect();
 
-        if tx.is_disputable() {
-            disputable_txs.insert(tx.tx, tx);
-        }
+    for tx in txs {
+        shards[shard_index(tx.client(), threads)].push(tx);
     }
 
-    Ok(accounts)
+    shards
 }
 
-fn write_accounts(accounts: AccountMap) -> Result<(), Box<dyn Error>> {
-    let mut wtr = csv::Writer::from_writer(io::stdout());
+fn process_shards_parallel(
+    shards: Vec<Vec<Transaction>>,
+    existential_deposit: Option<Decimal>,
+) -> Ledger {
+    let shard_ledgers: Vec<Ledger> = thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .map(|shard_txs| {
+                scope.spawn(move || process_tx_stream(shard_txs.into_iter(), existential_deposit))
+            })
+            .collect();
 
-    for account in accounts.into_values() {
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("shard worker thread panicked"))
+            .collect()
+    });
+
+    // every shard owns a disjoint set of clients, so accounts never collide
+    // and issuance simply sums across shards
+    let mut merged = Ledger::new(existential_deposit);
+    for shard in shard_ledgers {
+        merged.accounts.extend(shard.accounts);
+        merged.total_issuance += shard.total_issuance;
+    }
+
+    merged
+}
+
+fn balance_accounts_parallel(
+    mut tx_reader: Reader<File>,
+    threads: usize,
+    existential_deposit: Option<Decimal>,
+) -> Result<Ledger, Box<dyn Error>> {
+    let txs = tx_reader
+        .deserialize::<Transaction>()
+        .collect::<Result<Vec<Transaction>, _>>()?;
+
+    Ok(process_shards_parallel(
+        shard_by_client(txs, threads),
+        existential_deposit,
+    ))
+}
+
+fn write_accounts<W: Write>(accounts: &AccountMap, writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+
+    for account in accounts.values() {
         wtr.serialize(account)?;
     }
 
@@ -212,24 +684,175 @@ fn write_accounts(accounts: AccountMap) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn safe_run(path: &str) -> Result<(), Box<dyn Error>> {
+fn safe_run(
+    path: &str,
+    threads: usize,
+    existential_deposit: Option<Decimal>,
+) -> Result<(), Box<dyn Error>> {
     let tx_reader = ReaderBuilder::new()
         .flexible(true)
         .trim(Trim::All)
         .from_path(path)?;
 
-    let accounts = balance_accounts(tx_reader)?;
-    write_accounts(accounts)?;
+    let ledger = if threads <= 1 {
+        balance_accounts(tx_reader, existential_deposit)?
+    } else {
+        balance_accounts_parallel(tx_reader, threads, existential_deposit)?
+    };
+
+    write_accounts(&ledger.accounts, io::stdout())?;
+    eprintln!(
+        "total_issuance: {}",
+        ledger.total_issuance.round_dp(4).normalize()
+    );
+
+    Ok(())
+}
+
+// parses a single newline-delimited record using the same column layout as
+// the batch CSV input (type,client,tx,amount), just without a header row
+fn parse_tx_line(line: &str) -> Result<Transaction, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(Trim::All)
+        .from_reader(line.as_bytes());
+
+    reader
+        .deserialize::<Transaction>()
+        .next()
+        .ok_or("empty transaction record")?
+        .map_err(Into::into)
+}
+
+fn handle_connection(stream: TcpStream, ledger: &Arc<Mutex<Ledger>>) -> Result<(), Box<dyn Error>> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.eq_ignore_ascii_case("BALANCE") {
+            let snapshot = ledger.lock().expect("ledger mutex poisoned");
+            write_accounts(&snapshot.accounts, &mut writer)?;
+            continue;
+        }
+
+        match parse_tx_line(line) {
+            Ok(tx) => match ledger.lock().expect("ledger mutex poisoned").apply(tx) {
+                Ok(()) => writeln!(writer, "OK")?,
+                Err(e) => writeln!(writer, "ERROR {e}")?,
+            },
+            Err(e) => writeln!(writer, "ERROR {e}")?,
+        }
+    }
+
+    Ok(())
+}
+
+// runs the engine as a long-lived service: every connection feeds newline-
+// delimited tx records into one shared ledger, and the literal line "BALANCE"
+// reads back the current account snapshot on that same connection
+fn serve(port: u16, existential_deposit: Option<Decimal>) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    let ledger = Arc::new(Mutex::new(Ledger::new(existential_deposit)));
+
+    eprintln!("listening on 0.0.0.0:{port}");
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let ledger = Arc::clone(&ledger);
+
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &ledger) {
+                eprintln!("connection error: {e}");
+            }
+        });
+    }
 
     Ok(())
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let mut arg_iter = args.into_iter().skip(1);
+
+    let usage = "usage: tx-engine <path> [--threads N] [--existential-deposit AMOUNT] \
+                 | tx-engine serve [--port N] [--existential-deposit AMOUNT]";
+
+    let Some(first) = arg_iter.next() else {
+        eprintln!("{usage}");
+        process::exit(1);
+    };
 
-    let path = &args[1];
+    if first == "serve" {
+        let mut port: u16 = 7878;
+        let mut existential_deposit: Option<Decimal> = None;
 
-    match safe_run(path) {
+        while let Some(arg) = arg_iter.next() {
+            match arg.as_str() {
+                "--port" => {
+                    let value = arg_iter.next().expect("--port requires a value");
+                    port = value.parse().expect("--port value must be a valid port");
+                }
+                "--existential-deposit" => {
+                    let value = arg_iter
+                        .next()
+                        .expect("--existential-deposit requires a value");
+                    existential_deposit = Some(
+                        value
+                            .parse()
+                            .expect("--existential-deposit must be a decimal"),
+                    );
+                }
+                other => {
+                    eprintln!("unrecognized serve argument: {other}");
+                    process::exit(1);
+                }
+            }
+        }
+
+        if let Err(e) = serve(port, existential_deposit) {
+            dbg!(e);
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    let mut path = Some(first);
+    let mut threads: usize = 1;
+    let mut existential_deposit: Option<Decimal> = None;
+
+    while let Some(arg) = arg_iter.next() {
+        match arg.as_str() {
+            "--threads" => {
+                let value = arg_iter.next().expect("--threads requires a value");
+                threads = value
+                    .parse()
+                    .expect("--threads value must be a positive integer");
+            }
+            "--existential-deposit" => {
+                let value = arg_iter
+                    .next()
+                    .expect("--existential-deposit requires a value");
+                existential_deposit = Some(
+                    value
+                        .parse()
+                        .expect("--existential-deposit must be a decimal"),
+                );
+            }
+            _ => path = Some(arg),
+        }
+    }
+
+    let path = path.expect(usage);
+
+    match safe_run(&path, threads, existential_deposit) {
         Ok(()) => (),
         Err(e) => {
             dbg!(e);
@@ -242,97 +865,423 @@ fn main() {
 mod tests {
     use super::*;
 
+    fn tx(action: TxAction, client: u16, tx: u32, amount: Option<Decimal>) -> Transaction {
+        Transaction::try_from(TransactionRecord {
+            action,
+            client,
+            tx,
+            amount,
+        })
+        .unwrap()
+    }
+
     #[test]
-    fn test_process_tx() -> Result<(), Box<dyn Error>> {
+    fn test_process_tx() -> Result<(), LedgerError> {
         let mut accounts: AccountMap = AccountMap::new();
         let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
 
-        let mut deposit = Tx {
-            action: TxAction::DEPOSIT,
-            client: 1,
-            tx: 1,
-            amount: Some(dec!(1)),
-            is_disputed: false,
-        };
-
-        process_tx(&mut deposit, &mut disputable_txs, &mut accounts)?;
-        disputable_txs.insert(deposit.tx, deposit);
+        let deposit = tx(TxAction::DEPOSIT, 1, 1, Some(dec!(1)));
+        process_tx(&deposit, &mut disputable_txs, &mut states, &mut accounts)?;
+        disputable_txs.insert(deposit.tx(), deposit);
 
         let account = accounts.get(&1).unwrap();
         assert_eq!(account.total(), dec!(1));
 
-        let mut withdrawal = Tx {
-            action: TxAction::WITHDRAWAL,
-            client: 1,
-            tx: 2,
-            amount: Some(dec!(1)),
-            is_disputed: false,
-        };
-
-        process_tx(&mut withdrawal, &mut disputable_txs, &mut accounts)?;
+        let withdrawal = tx(TxAction::WITHDRAWAL, 1, 2, Some(dec!(1)));
+        process_tx(&withdrawal, &mut disputable_txs, &mut states, &mut accounts)?;
 
         let account = accounts.get(&1).unwrap();
         assert_eq!(account.total(), dec!(0));
 
-        let mut dispute = Tx {
-            action: TxAction::DISPUTE,
-            client: 1,
-            tx: 1,
-            amount: None,
-            is_disputed: false,
-        };
-
-        process_tx(&mut dispute, &mut disputable_txs, &mut accounts)?;
+        let dispute = tx(TxAction::DISPUTE, 1, 1, None);
+        process_tx(&dispute, &mut disputable_txs, &mut states, &mut accounts)?;
 
         let account = accounts.get(&1).unwrap();
         assert_eq!(account.total(), dec!(0));
         assert_eq!(account.available, dec!(-1));
         assert_eq!(account.held, dec!(1));
 
-        let mut resolve = Tx {
-            action: TxAction::RESOLVE,
-            client: 1,
-            tx: 1,
-            amount: None,
-            is_disputed: false,
-        };
+        let resolve = tx(TxAction::RESOLVE, 1, 1, None);
+        process_tx(&resolve, &mut disputable_txs, &mut states, &mut accounts)?;
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.total(), dec!(0));
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(0));
 
-        process_tx(&mut resolve, &mut disputable_txs, &mut accounts)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_chargeback_locks_account() -> Result<(), LedgerError> {
+        let mut accounts: AccountMap = AccountMap::new();
+        let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
+
+        let deposit = tx(TxAction::DEPOSIT, 1, 1, Some(dec!(1)));
+        process_tx(&deposit, &mut disputable_txs, &mut states, &mut accounts)?;
+        disputable_txs.insert(deposit.tx(), deposit);
+
+        let dispute = tx(TxAction::DISPUTE, 1, 1, None);
+        process_tx(&dispute, &mut disputable_txs, &mut states, &mut accounts)?;
+
+        let chargeback = tx(TxAction::CHARGEBACK, 1, 1, None);
+        process_tx(&chargeback, &mut disputable_txs, &mut states, &mut accounts)?;
 
         let account = accounts.get(&1).unwrap();
         assert_eq!(account.total(), dec!(0));
         assert_eq!(account.available, dec!(0));
         assert_eq!(account.held, dec!(0));
+        assert!(account.is_locked);
 
-        let mut redispute = Tx {
-            action: TxAction::DISPUTE,
+        Ok(())
+    }
+
+    #[test]
+    fn test_double_dispute_is_rejected() {
+        let mut accounts: AccountMap = AccountMap::new();
+        let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
+
+        let deposit = tx(TxAction::DEPOSIT, 1, 1, Some(dec!(1)));
+        process_tx(&deposit, &mut disputable_txs, &mut states, &mut accounts).unwrap();
+        disputable_txs.insert(deposit.tx(), deposit);
+
+        let dispute = tx(TxAction::DISPUTE, 1, 1, None);
+        process_tx(&dispute, &mut disputable_txs, &mut states, &mut accounts).unwrap();
+
+        let redispute = tx(TxAction::DISPUTE, 1, 1, None);
+        let result = process_tx(&redispute, &mut disputable_txs, &mut states, &mut accounts);
+        assert_eq!(result, Err(LedgerError::AlreadyDisputed(1, 1)));
+    }
+
+    #[test]
+    fn test_resolve_without_dispute_is_rejected() {
+        let mut accounts: AccountMap = AccountMap::new();
+        let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
+
+        let deposit = tx(TxAction::DEPOSIT, 1, 1, Some(dec!(1)));
+        process_tx(&deposit, &mut disputable_txs, &mut states, &mut accounts).unwrap();
+        disputable_txs.insert(deposit.tx(), deposit);
+
+        let resolve = tx(TxAction::RESOLVE, 1, 1, None);
+        let result = process_tx(&resolve, &mut disputable_txs, &mut states, &mut accounts);
+        assert_eq!(result, Err(LedgerError::NotDisputed(1, 1)));
+    }
+
+    #[test]
+    fn test_dispute_of_unknown_tx_is_rejected() {
+        let mut accounts: AccountMap = AccountMap::new();
+        let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
+
+        let dispute = tx(TxAction::DISPUTE, 1, 99, None);
+        let result = process_tx(&dispute, &mut disputable_txs, &mut states, &mut accounts);
+        assert_eq!(result, Err(LedgerError::UnknownTx(1, 99)));
+    }
+
+    #[test]
+    fn test_withdrawal_with_insufficient_funds_is_rejected() {
+        let mut accounts: AccountMap = AccountMap::new();
+        let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
+
+        let withdrawal = tx(TxAction::WITHDRAWAL, 1, 1, Some(dec!(1)));
+        let result = process_tx(&withdrawal, &mut disputable_txs, &mut states, &mut accounts);
+        assert_eq!(result, Err(LedgerError::NotEnoughFunds(1, dec!(1))));
+    }
+
+    #[test]
+    fn test_locked_account_rejects_deposit() {
+        let mut accounts: AccountMap = AccountMap::new();
+        let mut disputable_txs = TxMap::new();
+        let mut states = StateMap::new();
+
+        let deposit = tx(TxAction::DEPOSIT, 1, 1, Some(dec!(1)));
+        process_tx(&deposit, &mut disputable_txs, &mut states, &mut accounts).unwrap();
+        disputable_txs.insert(deposit.tx(), deposit);
+
+        let dispute = tx(TxAction::DISPUTE, 1, 1, None);
+        process_tx(&dispute, &mut disputable_txs, &mut states, &mut accounts).unwrap();
+
+        let chargeback = tx(TxAction::CHARGEBACK, 1, 1, None);
+        process_tx(&chargeback, &mut disputable_txs, &mut states, &mut accounts).unwrap();
+
+        let deposit_after_lock = tx(TxAction::DEPOSIT, 1, 2, Some(dec!(1)));
+        let result = process_tx(
+            &deposit_after_lock,
+            &mut disputable_txs,
+            &mut states,
+            &mut accounts,
+        );
+        assert_eq!(result, Err(LedgerError::FrozenAccount(1)));
+    }
+
+    #[test]
+    fn test_sharded_processing_matches_serial() {
+        let mut txs = Vec::new();
+        for client in 0..10u16 {
+            for seq in 0..20u32 {
+                let tx_id = u32::from(client) * 100 + seq;
+                txs.push(tx(TxAction::DEPOSIT, client, tx_id, Some(dec!(1))));
+            }
+        }
+
+        let serial = process_tx_stream(txs.clone().into_iter(), None);
+        let sharded = process_shards_parallel(shard_by_client(txs, 4), None);
+
+        assert_eq!(serial.accounts.len(), sharded.accounts.len());
+        for (client, account) in &serial.accounts {
+            let sharded_account = sharded.accounts.get(client).unwrap();
+            assert_eq!(account.total(), sharded_account.total());
+        }
+        assert_eq!(serial.total_issuance, sharded.total_issuance);
+    }
+
+    #[test]
+    fn test_existential_deposit_reaps_dust_accounts() {
+        let mut ledger = Ledger::new(Some(dec!(1)));
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(2)))).unwrap();
+        ledger
+            .apply(tx(TxAction::WITHDRAWAL, 1, 2, Some(dec!(1.5))))
+            .unwrap();
+
+        assert!(!ledger.accounts.contains_key(&1));
+        assert_eq!(ledger.total_issuance, dec!(0.5));
+    }
+
+    #[test]
+    fn test_deposit_below_threshold_reaps_account_without_a_withdrawal() {
+        let mut ledger = Ledger::new(Some(dec!(1)));
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(0.5)))).unwrap();
+
+        assert!(!ledger.accounts.contains_key(&1));
+        assert_eq!(ledger.total_issuance, dec!(0.5));
+    }
+
+    #[test]
+    fn test_chargeback_below_threshold_keeps_locked_account_and_reduces_issuance() {
+        let mut ledger = Ledger::new(Some(dec!(1)));
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(1)))).unwrap();
+        ledger.apply(tx(TxAction::DISPUTE, 1, 1, None)).unwrap();
+        ledger.apply(tx(TxAction::CHARGEBACK, 1, 1, None)).unwrap();
+
+        // a locked account is never reaped, dust or not: reaping it would let
+        // a later deposit recreate it unlocked via `ensure_account`, silently
+        // erasing the fraud lock
+        let account = ledger.accounts.get(&1).unwrap();
+        assert!(account.is_locked);
+        assert_eq!(ledger.total_issuance, dec!(0));
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 2, Some(dec!(100)))).unwrap_err();
+        let account = ledger.accounts.get(&1).unwrap();
+        assert!(account.is_locked);
+    }
+
+    #[test]
+    fn test_reserve_moves_funds_out_of_available_and_unreserve_returns_them() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(5)))).unwrap();
+        ledger.apply(tx(TxAction::RESERVE, 1, 2, Some(dec!(3)))).unwrap();
+
+        let account = ledger.accounts.get(&1).unwrap();
+        assert_eq!(account.available, dec!(2));
+        assert_eq!(account.reserved(), dec!(3));
+        assert_eq!(account.total(), dec!(5));
+
+        ledger.apply(tx(TxAction::UNRESERVE, 1, 2, None)).unwrap();
+
+        let account = ledger.accounts.get(&1).unwrap();
+        assert_eq!(account.available, dec!(5));
+        assert_eq!(account.reserved(), dec!(0));
+    }
+
+    #[test]
+    fn test_reserving_an_active_id_again_is_rejected_and_does_not_strand_funds() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(10)))).unwrap();
+        ledger.apply(tx(TxAction::RESERVE, 1, 5, Some(dec!(3)))).unwrap();
+
+        let err = ledger
+            .apply(tx(TxAction::RESERVE, 1, 5, Some(dec!(2))))
+            .unwrap_err();
+        assert_eq!(err, LedgerError::AlreadyReserved(1, 5));
+
+        let account = ledger.accounts.get(&1).unwrap();
+        assert_eq!(account.total(), dec!(10));
+        assert_eq!(account.reserved(), dec!(3));
+    }
+
+    #[test]
+    fn test_unreserve_of_unknown_id_is_rejected() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(5)))).unwrap();
+
+        let err = ledger.apply(tx(TxAction::UNRESERVE, 1, 2, None)).unwrap_err();
+        assert_eq!(err, LedgerError::UnknownTx(1, 2));
+    }
+
+    #[test]
+    fn test_lock_caps_withdrawable_amount() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(5)))).unwrap();
+        ledger.apply(tx(TxAction::LOCK, 1, 2, Some(dec!(4)))).unwrap();
+
+        let err = ledger
+            .apply(tx(TxAction::WITHDRAWAL, 1, 3, Some(dec!(2))))
+            .unwrap_err();
+        assert_eq!(err, LedgerError::NotEnoughFunds(1, dec!(2)));
+
+        ledger
+            .apply(tx(TxAction::WITHDRAWAL, 1, 4, Some(dec!(1))))
+            .unwrap();
+        assert_eq!(ledger.accounts.get(&1).unwrap().available, dec!(4));
+    }
+
+    #[test]
+    fn test_overlapping_locks_take_the_max_not_the_sum() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(5)))).unwrap();
+        ledger.apply(tx(TxAction::LOCK, 1, 2, Some(dec!(3)))).unwrap();
+        ledger.apply(tx(TxAction::LOCK, 1, 3, Some(dec!(4)))).unwrap();
+
+        let account = ledger.accounts.get(&1).unwrap();
+        assert_eq!(account.frozen(), dec!(4));
+        assert_eq!(account.withdrawable(), dec!(1));
+
+        ledger.apply(tx(TxAction::UNLOCK, 1, 3, None)).unwrap();
+
+        let account = ledger.accounts.get(&1).unwrap();
+        assert_eq!(account.frozen(), dec!(3));
+        assert_eq!(account.withdrawable(), dec!(2));
+    }
+
+    #[test]
+    fn test_locking_an_active_id_again_is_rejected() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(5)))).unwrap();
+        ledger.apply(tx(TxAction::LOCK, 1, 2, Some(dec!(3)))).unwrap();
+
+        let err = ledger
+            .apply(tx(TxAction::LOCK, 1, 2, Some(dec!(4))))
+            .unwrap_err();
+        assert_eq!(err, LedgerError::AlreadyLocked(1, 2));
+
+        let account = ledger.accounts.get(&1).unwrap();
+        assert_eq!(account.frozen(), dec!(3));
+    }
+
+    #[test]
+    fn test_unlock_of_unknown_id_is_rejected() {
+        let mut ledger = Ledger::new(None);
+
+        ledger.apply(tx(TxAction::DEPOSIT, 1, 1, Some(dec!(5)))).unwrap();
+
+        let err = ledger.apply(tx(TxAction::UNLOCK, 1, 2, None)).unwrap_err();
+        assert_eq!(err, LedgerError::UnknownTx(1, 2));
+    }
+
+    #[test]
+    fn test_deposit_record_missing_amount_is_rejected_at_parse_time() {
+        let record = TransactionRecord {
+            action: TxAction::DEPOSIT,
             client: 1,
             tx: 1,
             amount: None,
-            is_disputed: false,
         };
 
-        process_tx(&mut redispute, &mut disputable_txs, &mut accounts)?;
-
-        let account = accounts.get(&1).unwrap();
-        assert_eq!(account.total(), dec!(0));
-        assert_eq!(account.available, dec!(-1));
-        assert_eq!(account.held, dec!(1));
+        let err = Transaction::try_from(record).unwrap_err();
+        assert_eq!(err, ParseError::MissingAmount(TxAction::DEPOSIT));
+    }
 
-        let mut chargeback = Tx {
-            action: TxAction::CHARGEBACK,
+    #[test]
+    fn test_dispute_record_with_unexpected_amount_is_rejected_at_parse_time() {
+        let record = TransactionRecord {
+            action: TxAction::DISPUTE,
             client: 1,
             tx: 1,
-            amount: None,
-            is_disputed: false,
+            amount: Some(dec!(1)),
         };
 
-        process_tx(&mut chargeback, &mut disputable_txs, &mut accounts)?;
+        let err = Transaction::try_from(record).unwrap_err();
+        assert_eq!(err, ParseError::UnexpectedAmount(TxAction::DISPUTE));
+    }
 
-        let account = accounts.get(&1).unwrap();
-        assert_eq!(account.total(), dec!(-1));
-        assert_eq!(account.available, dec!(-1));
-        assert_eq!(account.held, dec!(0));
+    #[test]
+    fn test_parse_tx_line_rejects_withdrawal_missing_amount() {
+        let err = parse_tx_line("withdrawal,1,1,").unwrap_err();
+        assert!(err.to_string().contains("WITHDRAWAL record is missing a required amount"));
+    }
+
+    // run with: cargo test --release bench_shard_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_shard_throughput() {
+        use std::time::Instant;
+
+        let clients: u16 = 500;
+        let txs_per_client: u32 = 2_000;
+
+        let mut txs = Vec::with_capacity(usize::from(clients) * txs_per_client as usize);
+        for client in 0..clients {
+            for seq in 0..txs_per_client {
+                let tx_id = u32::from(client) * txs_per_client + seq;
+                txs.push(tx(TxAction::DEPOSIT, client, tx_id, Some(dec!(1))));
+            }
+        }
+
+        for threads in [1, 2, 4, 8] {
+            let shards = shard_by_client(txs.clone(), threads);
+            let start = Instant::now();
+            let ledger = process_shards_parallel(shards, None);
+            let elapsed = start.elapsed();
+
+            assert_eq!(ledger.accounts.len(), usize::from(clients));
+            println!("threads={threads:<2} elapsed={elapsed:?}");
+        }
+    }
+
+    #[test]
+    fn test_serve_accepts_txs_and_reports_balance() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let ledger = Arc::new(Mutex::new(Ledger::new(None)));
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &ledger).unwrap();
+        });
+
+        let mut client = TcpStream::connect(("127.0.0.1", port))?;
+        writeln!(client, "deposit,1,1,5.0")?;
+        writeln!(client, "BALANCE")?;
+
+        let mut reader = BufReader::new(client);
+        let mut ack = String::new();
+        reader.read_line(&mut ack)?;
+        assert_eq!(ack.trim(), "OK");
+
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        assert_eq!(
+            header.trim(),
+            "client,available,held,total,locked,reserved,frozen"
+        );
+
+        let mut snapshot = String::new();
+        reader.read_line(&mut snapshot)?;
+        assert_eq!(snapshot.trim(), "1,5,0,5,false,0,0");
 
         Ok(())
     }